@@ -0,0 +1,86 @@
+//! Blocking client for the amman relay.
+//!
+//! [`AmmanClient`] is a thin wrapper around [`crate::nonblocking::AmmanClient`]:
+//! it owns a current-thread tokio runtime and drives the async RPC methods with
+//! `block_on`, so the blocking and async surfaces stay in sync instead of being
+//! maintained as two parallel implementations. Account-state subscriptions are
+//! the exception: [`crate::subscription`] implements the blocking stream on its
+//! own reader thread.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::runtime::Runtime;
+
+use crate::nonblocking;
+
+pub type AmmanClientResult<T> = Result<T, AmmanClientError>;
+
+#[derive(Error, Debug)]
+pub enum AmmanClientError {
+    #[error("failed to talk to the amman relay")]
+    FailedToSendRequest(#[from] reqwest::Error),
+
+    #[error("failed to connect to the amman relay socket")]
+    RelayConnectionFailed(#[from] tungstenite::Error),
+
+    #[error("no account is labeled {0:?}")]
+    UnknownLabel(String),
+}
+
+/// Response to `request_known_address_labels`: a map of address to label.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddressLabelResponse {
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct AmmanClient {
+    inner: nonblocking::AmmanClient,
+    runtime: Arc<Runtime>,
+}
+
+impl AmmanClient {
+    pub fn new(amman_relay_uri: Option<String>) -> Self {
+        let runtime = Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to build tokio runtime for blocking amman client"),
+        );
+        Self {
+            inner: nonblocking::AmmanClient::new(amman_relay_uri),
+            runtime,
+        }
+    }
+
+    /// The runtime backing this client, shared with any blocking
+    /// [`crate::AmmanProcess`] built from it so they drive the same reactor.
+    pub(crate) fn runtime(&self) -> &Arc<Runtime> {
+        &self.runtime
+    }
+
+    /// The async client this one wraps.
+    pub(crate) fn nonblocking(&self) -> &nonblocking::AmmanClient {
+        &self.inner
+    }
+
+    pub(crate) fn relay_ws_uri(&self) -> String {
+        self.inner.relay_ws_uri()
+    }
+
+    pub fn request_validator_pid(&self) -> AmmanClientResult<u32> {
+        self.runtime.block_on(self.inner.request_validator_pid())
+    }
+
+    pub fn request_known_address_labels(&self) -> AmmanClientResult<AddressLabelResponse> {
+        self.runtime
+            .block_on(self.inner.request_known_address_labels())
+    }
+
+    pub fn request_kill_amman(&self) -> AmmanClientResult<()> {
+        self.runtime.block_on(self.inner.request_kill_amman())
+    }
+}
@@ -0,0 +1,8 @@
+pub mod amman_config;
+pub mod blocking;
+pub mod fs;
+pub mod nonblocking;
+pub mod subscription;
+pub mod test_utils;
+
+pub use test_utils::AmmanProcess;
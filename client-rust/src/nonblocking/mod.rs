@@ -0,0 +1,440 @@
+//! Async amman client and process lifecycle, built on tokio, for callers
+//! driving several validators or integrating into an async test harness.
+//!
+//! These async types are the source of truth for the readiness and RPC logic;
+//! the blocking [`crate::blocking::AmmanClient`] and [`crate::AmmanProcess`] are
+//! thin wrappers that `block_on` them, so the two APIs cannot drift. Mirrors the
+//! blocking→async split in the rbw agent. Account-state subscriptions are the
+//! exception: the blocking stream in [`crate::subscription`] has its own
+//! reader-thread implementation.
+
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{SinkExt, Stream, StreamExt};
+use tokio::{
+    net::TcpStream,
+    process::{Child, Command},
+    sync::mpsc,
+    time::{sleep, Instant},
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    amman_config::AmmanConfig,
+    blocking::{AddressLabelResponse, AmmanClientError, AmmanClientResult},
+    fs::write_amman_config,
+    subscription::AccountState,
+    test_utils::{
+        consts::{self, VALIDATOR_PORT, VALIDATOR_RPC_PORT},
+        AmmanProcessError, AmmanProcessResult, AutoKill, ProcessStatus, ReadinessConfig,
+    },
+};
+
+/// Async client for the amman relay.
+///
+/// The method set mirrors [`crate::blocking::AmmanClient`] exactly; only the
+/// `async`-ness differs.
+#[derive(Clone)]
+pub struct AmmanClient {
+    uri: String,
+    http: reqwest::Client,
+}
+
+impl AmmanClient {
+    pub fn new(amman_relay_uri: Option<String>) -> Self {
+        let uri = amman_relay_uri.unwrap_or_else(|| consts::AMMAN_RELAY_URI.to_string());
+        Self {
+            uri,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+    ) -> AmmanClientResult<T> {
+        let response = self
+            .http
+            .post(&self.uri)
+            .json(&serde_json::json!({ "method": method }))
+            .send()
+            .await
+            .map_err(AmmanClientError::from)?;
+        let payload = response.json::<T>().await.map_err(AmmanClientError::from)?;
+        Ok(payload)
+    }
+
+    pub async fn request_validator_pid(&self) -> AmmanClientResult<u32> {
+        self.request("amman_validator_pid").await
+    }
+
+    pub async fn request_known_address_labels(&self) -> AmmanClientResult<AddressLabelResponse> {
+        self.request("amman_known_address_labels").await
+    }
+
+    pub async fn request_kill_amman(&self) -> AmmanClientResult<()> {
+        self.request("amman_kill_amman").await
+    }
+
+    /// Subscribe to change notifications for `pubkey`, yielding snapshots as a
+    /// [`futures::Stream`]. Dropping the stream deregisters from the relay.
+    pub async fn subscribe_account_states(
+        &self,
+        pubkey: &str,
+    ) -> AmmanClientResult<AccountStateStream> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(self.relay_ws_uri())
+            .await
+            .map_err(AmmanClientError::from)?;
+        socket
+            .send(Message::Text(subscribe_message(pubkey)))
+            .await
+            .map_err(AmmanClientError::from)?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let account = pubkey.to_string();
+        let task = tokio::spawn(async move {
+            while let Some(Ok(message)) = socket.next().await {
+                if let Message::Text(body) = message {
+                    if let Ok(state) = serde_json::from_str::<AccountState>(&body) {
+                        if tx.send(state).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            let _ = socket.send(Message::Text(unsubscribe_message(&account))).await;
+            let _ = socket.close(None).await;
+        });
+
+        Ok(AccountStateStream {
+            receiver: rx,
+            task,
+        })
+    }
+
+    /// Subscribe by human-readable label, resolving it against the relay's
+    /// known address labels first.
+    pub async fn subscribe_labeled_accounts(
+        &self,
+        label: &str,
+    ) -> AmmanClientResult<AccountStateStream> {
+        let known = self.request_known_address_labels().await?;
+        let pubkey = known
+            .labels
+            .iter()
+            .find_map(|(address, name)| (name == label).then(|| address.clone()))
+            .ok_or_else(|| AmmanClientError::UnknownLabel(label.to_string()))?;
+        self.subscribe_account_states(&pubkey).await
+    }
+
+    pub(crate) fn relay_ws_uri(&self) -> String {
+        self.uri
+            .replacen("http://", "ws://", 1)
+            .replacen("https://", "wss://", 1)
+    }
+}
+
+/// An async stream of [`AccountState`] snapshots, the nonblocking counterpart
+/// of [`crate::subscription::AccountStateStream`].
+pub struct AccountStateStream {
+    receiver: mpsc::UnboundedReceiver<AccountState>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Stream for AccountStateStream {
+    type Item = AccountState;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for AccountStateStream {
+    fn drop(&mut self) {
+        // Abort the reader task; the relay also cleans up on disconnect.
+        self.task.abort();
+    }
+}
+
+fn subscribe_message(pubkey: &str) -> String {
+    serde_json::json!({ "subscribe": "account-state", "account": pubkey }).to_string()
+}
+
+fn unsubscribe_message(pubkey: &str) -> String {
+    serde_json::json!({ "unsubscribe": "account-state", "account": pubkey }).to_string()
+}
+
+/// Async counterpart of [`crate::AmmanProcess`].
+pub struct AmmanProcess {
+    process: Option<Child>,
+    status: ProcessStatus,
+    client: AmmanClient,
+    fixtures: PathBuf,
+    assets_dir: PathBuf,
+    readiness: ReadinessConfig,
+    auto_kill: AutoKill,
+}
+
+impl Drop for AmmanProcess {
+    fn drop(&mut self) {
+        if self.auto_kill == AutoKill::Disabled {
+            return;
+        }
+        // Only kill a child we spawned; externally started amman is left alone.
+        // `tokio::process::Child` does not kill on drop, so we must ask for it.
+        // Drop cannot await, so we send the kill signal without reaping — the
+        // child becomes a zombie only until this process exits, which is enough
+        // to free the validator ports for the next run.
+        if let Some(mut process) = self.process.take() {
+            let _ = process.start_kill();
+        }
+    }
+}
+
+impl AmmanProcess {
+    pub async fn new(client: AmmanClient) -> Self {
+        Self::new_with_readiness(client, ReadinessConfig::default()).await
+    }
+
+    pub async fn new_with_readiness(client: AmmanClient, readiness: ReadinessConfig) -> Self {
+        let status = match pid_of_amman_running_on_machine(&client).await {
+            Some(pid) => ProcessStatus::Running { pid },
+            None => ProcessStatus::Created,
+        };
+        let fixtures = std::fs::canonicalize(PathBuf::from("./tests/fixtures")).expect("fixtures");
+        let assets_dir =
+            std::fs::canonicalize(PathBuf::from("./tests/fixtures/assets")).expect("assets");
+        Self {
+            process: None,
+            status,
+            client,
+            fixtures,
+            assets_dir,
+            readiness,
+            auto_kill: AutoKill::default(),
+        }
+    }
+
+    pub fn status(&self) -> &ProcessStatus {
+        &self.status
+    }
+
+    /// Control whether an owned child is killed when this handle is dropped.
+    pub fn set_auto_kill(&mut self, auto_kill: AutoKill) {
+        self.auto_kill = auto_kill;
+    }
+
+    pub async fn ensure_started(&mut self) -> AmmanProcessResult<()> {
+        if matches!(self.status, ProcessStatus::Running { .. }) {
+            return Ok(());
+        }
+        if let Ok(pid) = self.client.request_validator_pid().await {
+            self.status = ProcessStatus::Running { pid };
+            return Ok(());
+        }
+        self.start().await
+    }
+
+    pub async fn start(&mut self) -> AmmanProcessResult<()> {
+        self.start_with(None).await
+    }
+
+    async fn start_with(&mut self, amman_config: Option<&mut AmmanConfig>) -> AmmanProcessResult<()> {
+        if !self.status.can_start() {
+            return Err(AmmanProcessError::InvalidTransition {
+                from: self.status.clone(),
+                to: ProcessStatus::Starting,
+            });
+        }
+        self.status = ProcessStatus::Starting;
+        match self._start(amman_config).await {
+            Ok(pid) => {
+                self.status = ProcessStatus::Running { pid };
+                Ok(())
+            }
+            Err(err) => {
+                self.status = ProcessStatus::Failed;
+                Err(err)
+            }
+        }
+    }
+
+    async fn _start(&mut self, amman_config: Option<&mut AmmanConfig>) -> AmmanProcessResult<u32> {
+        if let Some(pid) = pid_of_amman_running_on_machine(&self.client).await {
+            return Err(AmmanProcessError::AmmanAlreadyRunning(pid));
+        }
+
+        let mut cmd = Command::new(consts::AMMAN_EXECUTABLE);
+        cmd.current_dir(&self.fixtures);
+
+        if std::env::var(consts::DUMP_AMMAN).is_err() {
+            cmd.stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null());
+        }
+        // we hold on to the config_file to ensure it doesn't get dropped before we started amman
+        let (config_path, _config_file) = match amman_config {
+            Some(config) => {
+                if config.assets_folder.is_none() {
+                    config.assets_folder = self.assets_dir.to_str().map(str::to_owned);
+                }
+                let (path, file) = write_amman_config(&config);
+                (Some(path), Some(file))
+            }
+            None => (None, None),
+        };
+        cmd.arg("start");
+        if let Some(config_path) = config_path {
+            cmd.arg(config_path.to_str().unwrap());
+        }
+        eprintln!("Cmd: {:#?}", cmd);
+        let mut process = cmd.spawn()?;
+
+        // If readiness times out we must reap the child we just spawned: it is
+        // still a local binding (not yet in `self.process`), so tokio's `Child`
+        // drop would leave it running and the `AmmanProcess` drop cannot see it.
+        let pid = match self.wait_until_ready().await {
+            Ok(pid) => pid,
+            Err(err) => {
+                let _ = process.kill().await;
+                let _ = process.wait().await;
+                return Err(err);
+            }
+        };
+        self.process = Some(process);
+
+        Ok(pid)
+    }
+
+    async fn wait_until_ready(&self) -> AmmanProcessResult<u32> {
+        eprint!("\nWaiting for pid");
+        let pid = self
+            .poll_until(|client| async move {
+                pid_of_amman_running_on_machine(&client).await
+            })
+            .await?;
+        eprintln!(": {:#?}", pid);
+
+        eprint!("Waiting for validator to be ready: ");
+        self.wait_for_port(VALIDATOR_PORT, true).await?;
+        self.wait_for_port(VALIDATOR_RPC_PORT, true).await?;
+        eprint!("✔️\n");
+        Ok(pid)
+    }
+
+    pub async fn restart(&mut self, amman_config: &mut AmmanConfig) -> AmmanProcessResult<()> {
+        if !self.status.can_restart() {
+            return Err(AmmanProcessError::InvalidTransition {
+                from: self.status.clone(),
+                to: ProcessStatus::Starting,
+            });
+        }
+        if matches!(self.status, ProcessStatus::Running { .. }) {
+            self.kill(true).await?;
+        }
+        self.start_with(Some(amman_config)).await
+    }
+
+    pub async fn kill(&mut self, kill_external: bool) -> AmmanProcessResult<()> {
+        if !self.status.can_kill() {
+            return Err(AmmanProcessError::InvalidTransition {
+                from: self.status.clone(),
+                to: ProcessStatus::Stopping,
+            });
+        }
+        let pid = self.status.pid();
+        self.status = ProcessStatus::Stopping;
+
+        if let Some(mut process) = self.process.take() {
+            self.client
+                .request_kill_amman()
+                .await
+                .expect("should kill amman properly");
+
+            process.kill().await?;
+            process.wait().await?;
+            self.status = ProcessStatus::Stopped;
+        } else if let Some(pid) = pid {
+            if kill_external {
+                let mut process = Command::new(consts::AMMAN_EXECUTABLE).arg("stop").spawn()?;
+                process.wait().await?;
+                eprintln!("Waiting for validator to shut down");
+                self.wait_for_port(VALIDATOR_PORT, false).await?;
+                self.wait_for_port(VALIDATOR_RPC_PORT, false).await?;
+                self.status = ProcessStatus::Stopped;
+            } else {
+                eprintln!("Refusing to kill process that was not created by this runner ({:#?}). Please kill via `amman stop`",  pid);
+                self.status = ProcessStatus::Running { pid };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Poll `probe` until it yields `Some`, sleeping between attempts with the
+    /// exponential backoff described by [`ReadinessConfig`].
+    async fn poll_until<F, Fut>(&self, mut probe: F) -> AmmanProcessResult<u32>
+    where
+        F: FnMut(AmmanClient) -> Fut,
+        Fut: std::future::Future<Output = Option<u32>>,
+    {
+        let start = Instant::now();
+        let mut backoff = self.readiness.poll_interval;
+        loop {
+            if let Some(pid) = probe(self.client.clone()).await {
+                return Ok(pid);
+            }
+            if start.elapsed() >= self.readiness.overall_timeout {
+                return Err(AmmanProcessError::StartupTimedOut);
+            }
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(self.readiness.max_backoff);
+        }
+    }
+
+    /// Wait for `port` to be open (`want_open = true`) or released
+    /// (`want_open = false`), bounded by [`ReadinessConfig`].
+    async fn wait_for_port(&self, port: u16, want_open: bool) -> AmmanProcessResult<()> {
+        let start = Instant::now();
+        let mut backoff = self.readiness.poll_interval;
+        loop {
+            let open = TcpStream::connect(("0.0.0.0", port)).await.is_ok();
+            if open == want_open {
+                return Ok(());
+            }
+            if start.elapsed() >= self.readiness.overall_timeout {
+                return Err(AmmanProcessError::StartupTimedOut);
+            }
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(self.readiness.max_backoff);
+        }
+    }
+}
+
+pub async fn shutdown_amman() {
+    let client = AmmanClient::new(None);
+
+    if pid_of_amman_running_on_machine(&client).await.is_some() {
+        client
+            .request_kill_amman()
+            .await
+            .expect("failed to kill running amman");
+        // Wait for the pid to disappear with a bounded backoff rather than
+        // spinning on the relay.
+        let cfg = ReadinessConfig::default();
+        let start = Instant::now();
+        let mut backoff = cfg.poll_interval;
+        while pid_of_amman_running_on_machine(&client).await.is_some() {
+            if start.elapsed() >= cfg.overall_timeout {
+                break;
+            }
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(cfg.max_backoff);
+        }
+    }
+}
+
+pub async fn pid_of_amman_running_on_machine(client: &AmmanClient) -> Option<u32> {
+    client.request_validator_pid().await.ok()
+}
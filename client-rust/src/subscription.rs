@@ -0,0 +1,189 @@
+//! Streaming account-state subscriptions over the amman relay.
+//!
+//! [`AmmanClient::subscribe_account_states`] registers for an account's change
+//! notifications on the relay socket and yields decoded [`AccountState`]
+//! snapshots through an [`AccountStateStream`]. The stream tears down its relay
+//! registration on [`AccountStateStream::unsubscribe`] or drop. The channel
+//! plumbing follows constellation's `Sender`/`Receiver` split; the async
+//! `futures::Stream` variant lives in [`crate::nonblocking`].
+
+use std::net::{Shutdown, TcpStream};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, TryRecvError},
+    Arc,
+};
+use std::thread::{self, JoinHandle};
+
+use tungstenite::{stream::MaybeTlsStream, Message};
+
+use crate::blocking::{AmmanClient, AmmanClientError, AmmanClientResult};
+
+/// A single snapshot of an account as reported by the relay.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct AccountState {
+    /// Slot the snapshot was observed at.
+    pub slot: u64,
+    /// Raw account data.
+    pub data: Vec<u8>,
+    /// Human-readable label, when the account is known to amman.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// A live stream of [`AccountState`] snapshots for one account.
+///
+/// Snapshots arrive on an `mpsc` channel fed by a background reader thread.
+/// Dropping the stream (or calling [`AccountStateStream::unsubscribe`]) signals
+/// the reader to deregister from the relay and close the socket.
+pub struct AccountStateStream {
+    pubkey: String,
+    receiver: Receiver<AccountState>,
+    closed: Arc<AtomicBool>,
+    shutdown: Option<TcpStream>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl AccountStateStream {
+    pub(crate) fn new(
+        pubkey: String,
+        receiver: Receiver<AccountState>,
+        closed: Arc<AtomicBool>,
+        shutdown: Option<TcpStream>,
+        reader: JoinHandle<()>,
+    ) -> Self {
+        Self {
+            pubkey,
+            receiver,
+            closed,
+            shutdown,
+            reader: Some(reader),
+        }
+    }
+
+    /// The account this stream is watching.
+    pub fn pubkey(&self) -> &str {
+        &self.pubkey
+    }
+
+    /// Block until the next snapshot arrives, or `None` once the relay closes.
+    pub fn recv(&self) -> Option<AccountState> {
+        self.receiver.recv().ok()
+    }
+
+    /// Return a snapshot if one is already buffered without blocking.
+    pub fn try_recv(&self) -> Option<AccountState> {
+        match self.receiver.try_recv() {
+            Ok(state) => Some(state),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Deregister from the relay and wait for the reader thread to finish.
+    pub fn unsubscribe(mut self) {
+        self.teardown();
+    }
+
+    fn teardown(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        // Shut down the underlying socket so the reader thread's blocking
+        // `read()` returns instead of parking forever on a quiet subscription;
+        // only then can it observe `closed` and let `join()` complete.
+        if let Some(stream) = self.shutdown.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl Iterator for AccountStateStream {
+    type Item = AccountState;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+impl Drop for AccountStateStream {
+    fn drop(&mut self) {
+        self.teardown();
+    }
+}
+
+impl AmmanClient {
+    /// Subscribe to change notifications for `pubkey`.
+    pub fn subscribe_account_states(&self, pubkey: &str) -> AmmanClientResult<AccountStateStream> {
+        self.open_account_state_stream(pubkey.to_string())
+    }
+
+    /// Subscribe by human-readable label, resolving it against the relay's
+    /// known address labels first.
+    pub fn subscribe_labeled_accounts(
+        &self,
+        label: &str,
+    ) -> AmmanClientResult<AccountStateStream> {
+        let known = self.request_known_address_labels()?;
+        let pubkey = known
+            .labels
+            .iter()
+            .find_map(|(address, name)| (name == label).then(|| address.clone()))
+            .ok_or_else(|| AmmanClientError::UnknownLabel(label.to_string()))?;
+        self.subscribe_account_states(&pubkey)
+    }
+
+    fn open_account_state_stream(
+        &self,
+        pubkey: String,
+    ) -> AmmanClientResult<AccountStateStream> {
+        let (mut socket, _) =
+            tungstenite::connect(self.relay_ws_uri()).map_err(AmmanClientError::from)?;
+
+        // Register this account with the relay so it starts pushing updates.
+        socket
+            .send(Message::Text(subscribe_message(&pubkey)))
+            .map_err(AmmanClientError::from)?;
+
+        // Keep a clone of the underlying socket so `teardown` can shut it down
+        // and unblock the reader thread's `read()` on an idle subscription.
+        let shutdown = match socket.get_ref() {
+            MaybeTlsStream::Plain(stream) => stream.try_clone().ok(),
+            _ => None,
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let stop = closed.clone();
+        let account = pubkey.clone();
+
+        let reader = thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                match socket.read() {
+                    Ok(Message::Text(body)) => {
+                        if let Ok(state) = serde_json::from_str::<AccountState>(&body) {
+                            if tx.send(state).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(Message::Close(_)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+            // Best-effort deregistration; the relay also cleans up on disconnect.
+            let _ = socket.send(Message::Text(unsubscribe_message(&account)));
+            let _ = socket.close(None);
+        });
+
+        Ok(AccountStateStream::new(pubkey, rx, closed, shutdown, reader))
+    }
+}
+
+fn subscribe_message(pubkey: &str) -> String {
+    serde_json::json!({ "subscribe": "account-state", "account": pubkey }).to_string()
+}
+
+fn unsubscribe_message(pubkey: &str) -> String {
+    serde_json::json!({ "unsubscribe": "account-state", "account": pubkey }).to_string()
+}
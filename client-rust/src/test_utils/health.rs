@@ -0,0 +1,176 @@
+//! Background liveness monitoring for a running amman/solana-test-validator.
+//!
+//! A [`HealthMonitor`] polls the validator on an interval in its own thread,
+//! tracks consecutive probe failures, and publishes [`HealthStatus`]
+//! transitions over a channel. With an opt-in [`RestartPolicy`] it restarts a
+//! crashed validator via [`AmmanProcess::restart`] using the last-used config.
+//! The supervisor loop is modelled on putex's periodic healthcheck.
+
+use std::{
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    amman_config::AmmanConfig,
+    blocking::AmmanClient,
+    test_utils::{
+        consts::VALIDATOR_RPC_PORT, pid_of_amman_running_on_machine, AmmanProcess,
+    },
+};
+
+/// Liveness state derived from the most recent probes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// The last probe succeeded.
+    Healthy,
+    /// Some probes have failed but not enough to declare the validator down.
+    Degraded { consecutive_failures: u32 },
+    /// Enough consecutive probes have failed that the validator is considered gone.
+    Down,
+}
+
+/// How aggressively the monitor should try to bring a downed validator back.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Maximum number of restarts before the monitor gives up.
+    pub max_restarts: u32,
+    /// Delay before each restart attempt.
+    pub backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Tuning for the monitor loop.
+#[derive(Debug, Clone)]
+pub struct MonitorConfig {
+    /// Delay between probes.
+    pub poll_interval: Duration,
+    /// Consecutive probe failures required before the status becomes [`HealthStatus::Down`].
+    pub down_after: u32,
+    /// When set, the monitor restarts the validator once it goes down.
+    pub restart_policy: Option<RestartPolicy>,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            down_after: 3,
+            restart_policy: None,
+        }
+    }
+}
+
+/// A handle to a running monitor thread. Dropping it leaves the thread running;
+/// call [`HealthMonitor::stop`] to shut it down and join.
+pub struct HealthMonitor {
+    handle: Option<JoinHandle<()>>,
+    shutdown: Arc<AtomicBool>,
+    events: Receiver<HealthStatus>,
+}
+
+impl HealthMonitor {
+    /// Spawn a monitor for `amman`, probing `client` on the interval in `config`.
+    ///
+    /// `amman` and `amman_config` are only used when `config.restart_policy` is
+    /// set, in which case the monitor restarts the validator on the way down.
+    pub fn spawn(
+        client: AmmanClient,
+        mut amman: AmmanProcess,
+        amman_config: AmmanConfig,
+        config: MonitorConfig,
+    ) -> Self {
+        let (tx, events) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+
+        let handle = thread::spawn(move || {
+            let mut consecutive_failures = 0u32;
+            let mut restarts = 0u32;
+            let mut last = HealthStatus::Healthy;
+
+            while !stop.load(Ordering::Relaxed) {
+                let status = if probe(&client) {
+                    consecutive_failures = 0;
+                    HealthStatus::Healthy
+                } else {
+                    consecutive_failures += 1;
+                    if consecutive_failures >= config.down_after {
+                        HealthStatus::Down
+                    } else {
+                        HealthStatus::Degraded {
+                            consecutive_failures,
+                        }
+                    }
+                };
+
+                if status != last {
+                    // A disconnected receiver just means nobody is listening.
+                    let _ = tx.send(status.clone());
+                    last = status.clone();
+                }
+
+                if status == HealthStatus::Down {
+                    if let Some(policy) = &config.restart_policy {
+                        if restarts < policy.max_restarts {
+                            sleep(policy.backoff);
+                            // Count the attempt regardless of outcome, otherwise a
+                            // validator that keeps failing to restart would be
+                            // retried forever and never hit `max_restarts`.
+                            restarts += 1;
+                            let mut cfg = amman_config.clone();
+                            if amman.restart(&mut cfg).is_ok() {
+                                consecutive_failures = 0;
+                                let _ = tx.send(HealthStatus::Healthy);
+                                last = HealthStatus::Healthy;
+                            }
+                        }
+                    }
+                }
+
+                sleep(config.poll_interval);
+            }
+        });
+
+        Self {
+            handle: Some(handle),
+            shutdown,
+            events,
+        }
+    }
+
+    /// The receiving end of the status-transition channel. Only transitions
+    /// (not every probe) are sent, so tests can assert on crashes and recoveries.
+    pub fn events(&self) -> &Receiver<HealthStatus> {
+        &self.events
+    }
+
+    /// Signal the monitor thread to stop and wait for it to finish.
+    pub fn stop(mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The validator is up only if amman reports a pid *and* the RPC port accepts
+/// connections — the pid alone does not mean the validator is answering.
+fn probe(client: &AmmanClient) -> bool {
+    pid_of_amman_running_on_machine(client).is_some()
+        && TcpStream::connect(("0.0.0.0", VALIDATOR_RPC_PORT)).is_ok()
+}
@@ -1,183 +1,221 @@
 use std::{
-    io,
-    net::TcpStream,
-    path::PathBuf,
-    process::{Child, Command, Stdio},
+    fmt, io,
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
+use tokio::runtime::Runtime;
 
-use crate::{
-    amman_config::AmmanConfig,
-    blocking::AmmanClient,
-    fs::write_amman_config,
-    test_utils::consts::{VALIDATOR_PORT, VALIDATOR_RPC_PORT},
-};
+use crate::{amman_config::AmmanConfig, blocking::AmmanClient, nonblocking};
 
 pub type AmmanProcessResult<T> = Result<T, AmmanProcessError>;
 
 pub mod consts;
 pub mod fs;
+pub mod health;
 
 #[derive(Error, Debug)]
 pub enum AmmanProcessError {
-    #[error("amman was already started")]
-    AmmanWasAlreadyStarted,
-
     #[error("amman already running on this machine with pid {0}, please kill it first and then continue")]
     AmmanAlreadyRunning(u32),
 
-    #[error("amman is not running and thus cannot be killed")]
-    AmmanCannotBeKilledIfNotRunning,
-
     #[error("failed to kill amman")]
     FailedToKillAmman(#[from] io::Error),
+
+    #[error("failed to install signal handler")]
+    FailedToInstallSignalHandler(#[source] io::Error),
+
+    #[error("timed out waiting for amman to start up or shut down")]
+    StartupTimedOut,
+
+    #[error("invalid amman process transition from {from} to {to}")]
+    InvalidTransition {
+        from: ProcessStatus,
+        to: ProcessStatus,
+    },
 }
 
-pub struct AmmanProcess {
-    process: Option<Child>,
-    pid: Option<u32>,
-    client: AmmanClient,
-    fixtures: PathBuf,
-    assets_dir: PathBuf,
+/// Lifecycle state of an [`AmmanProcess`].
+///
+/// Every mutating operation consults the current status through one of the
+/// `can_*` guards before advancing it, so that `start`/`restart`/`kill` can no
+/// longer interleave in ways that leave the handle inconsistent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessStatus {
+    /// Freshly constructed, nothing is known to be running.
+    Created,
+    /// A start has been issued but amman has not reported a pid yet.
+    Starting,
+    /// Amman is up and answering RPC with the given pid.
+    Running { pid: u32 },
+    /// A kill has been issued but the port has not been released yet.
+    Stopping,
+    /// Amman was running and has since been shut down.
+    Stopped,
+    /// The last transition failed and the handle is in an unknown state.
+    Failed,
 }
 
-impl Clone for AmmanProcess {
-    fn clone(&self) -> Self {
-        // Cannot clone the process, thus this mainly serves to not have to query the pid
-        // for an externally running amman again.
-        // It is mainly used when attempting to restart the validator.
-        Self {
-            process: None,
-            pid: self.pid.clone(),
-            client: self.client.clone(),
-            fixtures: self.fixtures.clone(),
-            assets_dir: self.assets_dir.clone(),
+impl ProcessStatus {
+    /// Whether a fresh `start` may be issued from this status.
+    pub fn can_start(&self) -> bool {
+        matches!(
+            self,
+            ProcessStatus::Created | ProcessStatus::Stopped | ProcessStatus::Failed
+        )
+    }
+
+    /// Whether the process is live enough to be killed.
+    pub fn can_kill(&self) -> bool {
+        matches!(self, ProcessStatus::Running { .. })
+    }
+
+    /// Whether a restart may be issued, i.e. we are not mid-transition.
+    pub fn can_restart(&self) -> bool {
+        !matches!(self, ProcessStatus::Starting | ProcessStatus::Stopping)
+    }
+
+    /// The pid amman reported, if we believe it is running.
+    pub fn pid(&self) -> Option<u32> {
+        match self {
+            ProcessStatus::Running { pid } => Some(*pid),
+            _ => None,
         }
     }
 }
 
-impl AmmanProcess {
-    pub fn new(client: AmmanClient) -> Self {
-        let pid = pid_of_amman_running_on_machine(&client);
-        let fixtures = std::fs::canonicalize(PathBuf::from("./tests/fixtures")).expect("fixtures");
-        let assets_dir =
-            std::fs::canonicalize(PathBuf::from("./tests/fixtures/assets")).expect("assets");
+impl fmt::Display for ProcessStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessStatus::Created => write!(f, "created"),
+            ProcessStatus::Starting => write!(f, "starting"),
+            ProcessStatus::Running { pid } => write!(f, "running({})", pid),
+            ProcessStatus::Stopping => write!(f, "stopping"),
+            ProcessStatus::Stopped => write!(f, "stopped"),
+            ProcessStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Controls how [`AmmanProcess`] waits for amman and the validator to come up.
+///
+/// Readiness is probed on an interval that grows exponentially from
+/// `poll_interval` up to `max_backoff`, giving up once `overall_timeout`
+/// elapses so a never-ready amman fails a test run instead of wedging it.
+#[derive(Debug, Clone)]
+pub struct ReadinessConfig {
+    /// Delay before the first retry, and the base that is doubled each attempt.
+    pub poll_interval: Duration,
+    /// Upper bound the doubling backoff is clamped to.
+    pub max_backoff: Duration,
+    /// Time a single wait (pid discovery, or one port check) may take before
+    /// returning [`AmmanProcessError::StartupTimedOut`]. The waits of one start
+    /// run one after another, each with its own deadline.
+    pub overall_timeout: Duration,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
         Self {
-            process: None,
-            pid,
-            client,
-            fixtures,
-            assets_dir,
+            poll_interval: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            overall_timeout: Duration::from_secs(60),
         }
     }
+}
 
-    pub fn ensure_started(&mut self) -> AmmanProcessResult<()> {
-        if self.process.is_some() {
+/// Poll `cond` until it returns `true`, sleeping between attempts with an
+/// exponential backoff as described by `cfg`. Returns
+/// [`AmmanProcessError::StartupTimedOut`] once `cfg.overall_timeout` elapses.
+pub fn poll_until<F: FnMut() -> bool>(
+    mut cond: F,
+    cfg: &ReadinessConfig,
+) -> AmmanProcessResult<()> {
+    let start = Instant::now();
+    let mut backoff = cfg.poll_interval;
+    loop {
+        if cond() {
             return Ok(());
         }
-        if let Some(pid) = self.client.request_validator_pid().ok() {
-            self.pid = Some(pid);
-            return Ok(());
+        if start.elapsed() >= cfg.overall_timeout {
+            return Err(AmmanProcessError::StartupTimedOut);
         }
-        self.start()
+        sleep(backoff);
+        backoff = (backoff * 2).min(cfg.max_backoff);
     }
+}
 
-    pub fn start(&mut self) -> AmmanProcessResult<()> {
-        self._start(None)?;
-        Ok(())
+/// Whether an [`AmmanProcess`] should kill an owned child when it is dropped.
+///
+/// Externally started amman is never killed on drop regardless of this flag;
+/// this only governs children this runner spawned. Long-lived callers that
+/// intentionally keep amman alive past the handle can opt out with
+/// [`AutoKill::Disabled`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoKill {
+    Enabled,
+    Disabled,
+}
+
+impl Default for AutoKill {
+    fn default() -> Self {
+        AutoKill::Enabled
     }
+}
 
-    fn _start(&mut self, amman_config: Option<&mut AmmanConfig>) -> AmmanProcessResult<()> {
-        if self.process.is_some() {
-            return Err(AmmanProcessError::AmmanWasAlreadyStarted);
-        }
-        if let Some(pid) = pid_of_amman_running_on_machine(&self.client) {
-            return Err(AmmanProcessError::AmmanAlreadyRunning(pid));
-        }
+/// Blocking handle to a spawned (or externally running) amman validator.
+///
+/// This is a thin wrapper that drives [`crate::nonblocking::AmmanProcess`] with
+/// `block_on` on the runtime owned by its [`AmmanClient`], so the blocking and
+/// async lifecycles stay in sync rather than being maintained twice.
+pub struct AmmanProcess {
+    inner: nonblocking::AmmanProcess,
+    runtime: Arc<Runtime>,
+}
 
-        let mut cmd = Command::new(consts::AMMAN_EXECUTABLE);
-        cmd.current_dir(&self.fixtures);
+impl AmmanProcess {
+    pub fn new(client: AmmanClient) -> Self {
+        Self::new_with_readiness(client, ReadinessConfig::default())
+    }
 
-        if std::env::var(consts::DUMP_AMMAN).is_err() {
-            cmd.stdout(Stdio::null()).stderr(Stdio::null());
-        }
-        // we hold on to the config_file to ensure it doesn't get dropped before we started amman
-        let (config_path, _config_file) = match amman_config {
-            Some(config) => {
-                if config.assets_folder.is_none() {
-                    config.assets_folder = self.assets_dir.to_str().map(str::to_owned);
-                }
-                let (path, file) = write_amman_config(&config);
-                (Some(path), Some(file))
-            }
-            None => (None, None),
-        };
-        cmd.arg("start");
-        if let Some(config_path) = config_path {
-            cmd.arg(config_path.to_str().unwrap());
-        }
-        eprintln!("Cmd: {:#?}", cmd);
-        let process = cmd.spawn()?;
-
-        eprint!("\nWaiting for pid");
-        loop {
-            match pid_of_amman_running_on_machine(&self.client) {
-                Some(pid) => {
-                    eprintln!(": {:#?}", pid);
-                    break;
-                }
-                None => {}
-            }
-        }
+    pub fn new_with_readiness(client: AmmanClient, readiness: ReadinessConfig) -> Self {
+        let runtime = client.runtime().clone();
+        let inner = runtime.block_on(nonblocking::AmmanProcess::new_with_readiness(
+            client.nonblocking().clone(),
+            readiness,
+        ));
+        Self { inner, runtime }
+    }
 
-        eprint!("Waiting for validator to be ready: ");
-        wait_for_port(VALIDATOR_PORT);
-        wait_for_port(VALIDATOR_RPC_PORT);
-        eprint!("✔️\n");
-        self.process = Some(process);
+    /// The current lifecycle status of the handle.
+    pub fn status(&self) -> &ProcessStatus {
+        self.inner.status()
+    }
 
-        Ok(())
+    /// Control whether an owned child is killed when this handle is dropped.
+    pub fn set_auto_kill(&mut self, auto_kill: AutoKill) {
+        self.inner.set_auto_kill(auto_kill);
     }
 
-    pub fn restart(&mut self, amman_config: &mut AmmanConfig) -> AmmanProcessResult<()> {
-        if self.started() {
-            self.kill(true)?;
-        }
-        self._start(Some(amman_config))?;
-        Ok(())
+    pub fn ensure_started(&mut self) -> AmmanProcessResult<()> {
+        self.runtime.block_on(self.inner.ensure_started())
     }
 
-    pub fn kill(&mut self, kill_external: bool) -> AmmanProcessResult<()> {
-        if !self.started() {
-            return Err(AmmanProcessError::AmmanCannotBeKilledIfNotRunning);
-        }
+    pub fn start(&mut self) -> AmmanProcessResult<()> {
+        self.runtime.block_on(self.inner.start())
+    }
 
-        if let Some(process) = self.process.as_mut() {
-            self.client
-                .request_kill_amman()
-                .expect("should kill amman properly");
-
-            process.kill()?;
-            process.wait()?;
-            self.process = None;
-        } else if let Some(pid) = self.pid {
-            if kill_external {
-                let mut process = Command::new(consts::AMMAN_EXECUTABLE).arg("stop").spawn()?;
-                process.wait()?;
-                eprintln!("Waiting for validator to shut down");
-                wait_for_port_free(VALIDATOR_PORT);
-                wait_for_port_free(VALIDATOR_RPC_PORT);
-                self.pid = None;
-            } else {
-                eprintln!("Refusing to kill process that was not created by this runner ({:#?}). Please kill via `amman stop`",  pid);
-            }
-        }
+    pub fn restart(&mut self, amman_config: &mut AmmanConfig) -> AmmanProcessResult<()> {
+        self.runtime.block_on(self.inner.restart(amman_config))
+    }
 
-        Ok(())
+    pub fn kill(&mut self, kill_external: bool) -> AmmanProcessResult<()> {
+        self.runtime.block_on(self.inner.kill(kill_external))
     }
 
     pub fn started(&self) -> bool {
-        self.process.is_some() || self.pid.is_some()
+        matches!(self.inner.status(), ProcessStatus::Running { .. })
     }
 }
 
@@ -188,28 +226,38 @@ pub fn shutdown_amman() {
         client
             .request_kill_amman()
             .expect("failed to kill running amman");
-        while pid_of_amman_running_on_machine(&client).is_some() {}
+        // Wait for the pid to disappear with a bounded backoff rather than
+        // spinning a core on the relay.
+        let _ = poll_until(
+            || pid_of_amman_running_on_machine(&client).is_none(),
+            &ReadinessConfig::default(),
+        );
     }
 }
 
+/// Install SIGINT/SIGTERM handlers that gracefully shut down amman before the
+/// process exits, so a `Ctrl-C`'d or terminated test run does not leak a
+/// running validator. Modelled on vore's daemon signal draining.
+pub fn install_signal_handler() -> AmmanProcessResult<()> {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])
+        .map_err(AmmanProcessError::FailedToInstallSignalHandler)?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            shutdown_amman();
+            std::process::exit(0);
+        }
+    });
+    Ok(())
+}
+
 pub fn pid_of_amman_running_on_machine(client: &AmmanClient) -> Option<u32> {
     match client.request_validator_pid() {
         Ok(pid) => Some(pid),
         Err(_) => None,
     }
 }
-
-fn scan_port(port: u16) -> bool {
-    match TcpStream::connect(("0.0.0.0", port)) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
-}
-
-fn wait_for_port(port: u16) {
-    while !scan_port(port) {}
-}
-
-fn wait_for_port_free(port: u16) {
-    while scan_port(port) {}
-}